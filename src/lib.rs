@@ -0,0 +1,5 @@
+pub mod chunked_sigv4;
+pub mod no_cache;
+pub mod nonce_store;
+pub mod param_verify;
+pub mod slack_verify;
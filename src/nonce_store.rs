@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+
+/// A time-bounded set of recently-seen signed-request identifiers, used to reject replays of an
+/// otherwise validly signed request within its allowed time window.
+///
+/// Implement this to back replay protection with a shared store (e.g. Redis) for multi-instance
+/// deployments; [`InMemoryNonceStore`] is the single-instance default.
+///
+/// Annotated with `#[async_trait]` so the trait stays object-safe (`Arc<dyn NonceStore>`), since
+/// a plain `async fn` in a trait cannot be used as a trait object on stable Rust.
+#[async_trait]
+pub trait NonceStore: Send + Sync {
+    /// Records `nonce` as seen, expiring at `now + ttl` (unix seconds). Returns `true` if this is
+    /// the first time `nonce` has been seen while still within its TTL, `false` if it's a replay.
+    async fn check_and_insert(&self, nonce: &str, now: i64, ttl: i64) -> bool;
+}
+
+/// The default [`NonceStore`]: an in-memory map, guarded by a `tokio` mutex, that opportunistically
+/// evicts expired entries whenever a new nonce is checked.
+#[derive(Default)]
+pub struct InMemoryNonceStore {
+    seen: Mutex<HashMap<String, i64>>,
+}
+
+impl InMemoryNonceStore {
+    #[must_use]
+    pub fn new() -> InMemoryNonceStore {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl NonceStore for InMemoryNonceStore {
+    async fn check_and_insert(&self, nonce: &str, now: i64, ttl: i64) -> bool {
+        let mut seen = self.seen.lock().await;
+        seen.retain(|_, expires_at| *expires_at > now);
+
+        if seen.contains_key(nonce) {
+            return false;
+        }
+        seen.insert(nonce.to_string(), now + ttl);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InMemoryNonceStore, NonceStore};
+
+    #[tokio::test]
+    async fn test_rejects_repeated_nonce_within_window() {
+        let store = InMemoryNonceStore::new();
+        assert!(store.check_and_insert("nonce-a", 1_000, 300).await);
+        assert!(!store.check_and_insert("nonce-a", 1_001, 300).await);
+    }
+
+    #[tokio::test]
+    async fn test_allows_nonce_again_after_expiry() {
+        let store = InMemoryNonceStore::new();
+        assert!(store.check_and_insert("nonce-a", 1_000, 300).await);
+        assert!(store.check_and_insert("nonce-a", 1_301, 300).await);
+    }
+}